@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    MalformedBinaryParse,
+    UnknownType,
+    Io,
+}
+
+#[derive(Debug)]
+pub struct WeechatParseError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for WeechatParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.detail {
+            Some(ref detail) => write!(f, "{} ({:?}): {}", self.message, self.kind, detail),
+            None => write!(f, "{} ({:?})", self.message, self.kind),
+        }
+    }
+}
+
+impl Error for WeechatParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl<'a> From<(ErrorKind, &'a str)> for WeechatParseError {
+    fn from((kind, message): (ErrorKind, &'a str)) -> WeechatParseError {
+        WeechatParseError {
+            kind: kind,
+            message: message.to_owned(),
+            detail: None,
+        }
+    }
+}
+
+impl<'a> From<(ErrorKind, &'a str, String)> for WeechatParseError {
+    fn from((kind, message, detail): (ErrorKind, &'a str, String)) -> WeechatParseError {
+        WeechatParseError {
+            kind: kind,
+            message: message.to_owned(),
+            detail: Some(detail),
+        }
+    }
+}
+
+impl From<io::Error> for WeechatParseError {
+    fn from(err: io::Error) -> WeechatParseError {
+        WeechatParseError {
+            kind: ErrorKind::Io,
+            message: "I/O error while parsing".to_owned(),
+            detail: Some(err.to_string()),
+        }
+    }
+}
+
+impl From<ParseIntError> for WeechatParseError {
+    fn from(err: ParseIntError) -> WeechatParseError {
+        WeechatParseError {
+            kind: ErrorKind::MalformedBinaryParse,
+            message: "Could not parse integer".to_owned(),
+            detail: Some(err.to_string()),
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! fail {
+    ($expr:expr) => (
+        return Err(::std::convert::From::from($expr))
+    )
+}
@@ -1,18 +1,20 @@
-extern crate byteorder;
+#[macro_use]
+extern crate nom;
 extern crate flate2;
+extern crate zstd;
 
 #[macro_use]
 pub mod errors;
 
 use std::char;
-use std::io::Cursor;
 use std::io::prelude::*;
 use std::string::String;
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::thread;
-use byteorder::{ReadBytesExt, BigEndian};
 use flate2::read::ZlibDecoder;
+use zstd::Decoder as ZstdDecoder;
+use nom::{IResult, ErrorKind, be_i32, be_u8, be_u32};
 use errors::WeechatParseError;
 use errors::ErrorKind::{MalformedBinaryParse, UnknownType};
 
@@ -44,15 +46,17 @@ pub enum WeechatData {
     Time(String),
     Array(Vec<WeechatData>),
     Hdata(String, Vec<WeechatData>, Vec<HashMap<String, WeechatData>>),
+    HashTable(String, String, Vec<(WeechatData, WeechatData)>),
+    Info(String, Option<String>),
+    InfoList(String, Vec<HashMap<String, WeechatData>>),
 }
 
 impl WeechatMessage {
     pub fn from_raw_message(buffer: &[u8]) -> Result<WeechatMessage, WeechatParseError> {
         let raw_data = try!(get_raw_data(&buffer));
         let (len, id) = try!(get_message_type(&raw_data));
-        let length = raw_data.len() - len;
         let name = id.unwrap_or("test".to_owned());
-        let data = try!(parse_data(&raw_data[len..], length));
+        let data = try!(nom_to_result(parse_data(&raw_data[len..]), "message body"));
         Ok(WeechatMessage { id: name, data: data })
     }
 }
@@ -64,6 +68,95 @@ pub fn new() -> (Sender<Vec<u8>>, Receiver<Result<WeechatMessage, WeechatParseEr
     (tx_in, rx_out)
 }
 
+/// Builds an outgoing relay command line, in the streaming-builder style:
+/// start from a command name, `append` each typed piece, then `out()` the
+/// finished, newline-terminated bytes ready to write to the relay socket.
+pub struct WeechatCommand {
+    id: Option<String>,
+    command: String,
+    args: Vec<String>,
+}
+
+impl WeechatCommand {
+    fn new(command: &str) -> WeechatCommand {
+        WeechatCommand {
+            id: None,
+            command: command.to_owned(),
+            args: vec![],
+        }
+    }
+
+    /// Prefixes the command with `(id)` so the matching reply can be told apart.
+    pub fn with_id(mut self, id: &str) -> WeechatCommand {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    fn append(mut self, arg: String) -> WeechatCommand {
+        self.args.push(arg);
+        self
+    }
+
+    pub fn init(password: &str, compression: Compression) -> WeechatCommand {
+        let compression_name = match compression {
+            Compression::None => "off",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+        };
+        WeechatCommand::new("init")
+            .append(format!("password={},compression={}", password, compression_name))
+    }
+
+    pub fn hdata(path: &str, keys: &[&str]) -> WeechatCommand {
+        let command = WeechatCommand::new("hdata").append(path.to_owned());
+        if keys.is_empty() {
+            command
+        } else {
+            command.append(keys.join(","))
+        }
+    }
+
+    pub fn sync(buffers: &[&str]) -> WeechatCommand {
+        let command = WeechatCommand::new("sync");
+        if buffers.is_empty() {
+            command
+        } else {
+            command.append(buffers.join(","))
+        }
+    }
+
+    pub fn nicklist(buffer: &str) -> WeechatCommand {
+        WeechatCommand::new("nicklist").append(buffer.to_owned())
+    }
+
+    pub fn input(buffer: &str, data: &str) -> WeechatCommand {
+        WeechatCommand::new("input")
+            .append(buffer.to_owned())
+            .append(data.to_owned())
+    }
+
+    pub fn ping(payload: &str) -> WeechatCommand {
+        WeechatCommand::new("ping").append(payload.to_owned())
+    }
+
+    /// Renders the finished, newline-terminated command line as bytes.
+    pub fn out(self) -> Vec<u8> {
+        let mut line = String::new();
+        if let Some(id) = self.id {
+            line.push('(');
+            line.push_str(&id);
+            line.push(')');
+        }
+        line.push_str(&self.command);
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(arg);
+        }
+        line.push('\n');
+        line.into_bytes()
+    }
+}
+
 #[macro_export]
 macro_rules! try_send_error {
     ($output:ident, $expr:expr) => (
@@ -105,232 +198,355 @@ fn start_parser(input: Receiver<Vec<u8>>,
     }
 }
 
-fn parse_data(buffer: &[u8], length: usize) -> Result<Vec<WeechatData>, WeechatParseError> {
-    let mut acc = vec![];
-    let mut position = 0;
-    while position < length {
-        let element_type = get_element_type(&buffer[position..]);
-        position += 3;
-        let (len, value) = try!(parse_element(&element_type, &buffer[position..]));
-        position += len;
-        acc.push(value);
-    }
-    Ok(acc)
-}
-
-fn parse_element(element_type: &str,
-                 buffer: &[u8])
-                 -> Result<(usize, WeechatData), WeechatParseError> {
-    match element_type {
-        "chr" => {
-            let value = try!(read_u8(&buffer));
-            let input_char = try!(char::from_u32(value as u32).ok_or((MalformedBinaryParse,
-                                                                      "Couldn't read char data")));
-            Ok((1, WeechatData::Char(input_char)))
-        }
-        "int" => {
-            let value = try!(read_i32(&buffer));
-            Ok((4, WeechatData::Int(value)))
+/// Turns the outcome of a top-level nom parser into the crate's own error
+/// type, so call sites can keep using `try!`/`fail!` like the rest of the
+/// parser instead of matching on `IResult` themselves.
+fn nom_to_result<T>(result: IResult<&[u8], T>, what: &str) -> Result<T, WeechatParseError> {
+    match result {
+        IResult::Done(_, value) => Ok(value),
+        IResult::Error(ErrorKind::Custom(UNKNOWN_TYPE_ERROR)) => {
+            fail!((UnknownType, "Got unfamiliar type", what.to_owned()))
         }
-        "lon" => {
-            let (len, value) = try!(read_long(&buffer));
-            Ok((len, WeechatData::Long(value)))
+        IResult::Error(e) => {
+            fail!((MalformedBinaryParse,
+                   "Malformed binary data",
+                   format!("while parsing {}: {:?}", what, e)))
         }
-        "str" => {
-            let (len, value) = try!(read_string_32bit_length(&buffer));
-            match value {
-                Some(string) => Ok((len, WeechatData::String(string))),
-                None => Ok((len, WeechatData::StringNull)),
-            }
+        IResult::Incomplete(needed) => {
+            fail!((MalformedBinaryParse,
+                   "Message ended before it was fully read",
+                   format!("while parsing {}: {:?}", what, needed)))
         }
-        "buf" => {
-            let (len, value) = try!(read_string_32bit_length(&buffer));
-            match value {
-                Some(string) => Ok((len, WeechatData::Buffer(string))),
-                None => Ok((len, WeechatData::BufferNull)),
-            }
-        }
-        "ptr" => {
-            let (len, value) = try!(read_pointer(&buffer));
-            Ok((len, WeechatData::Pointer(value)))
-        }
-        "tim" => {
-            let (len, value) = try!(read_time(&buffer));
-            Ok((len, WeechatData::Time(value)))
-        }
-        // "htb" => break,
-        "hda" => {
-            let (len, name, pointers, value) = try!(read_hdata(&buffer));
-            Ok((len, WeechatData::Hdata(name, pointers, value)))
-        }
-        // "inf" => break,
-        // "inl" => break,
-        "arr" => {
-            let (len, value) = try!(read_array(&buffer));
-            Ok((len, WeechatData::Array(value)))
-        }
-        _ => Err(WeechatParseError::from((UnknownType,
-                                          "Got unfamiliar type",
-                                          element_type.to_owned()))),
     }
 }
 
-fn get_element_type(buffer: &[u8]) -> String {
-    String::from_utf8_lossy(&buffer[..3]).into_owned()
+/// Wire error code for an element type tag that isn't one of the known
+/// wire types, kept distinct from other parse failures so callers can tell
+/// "an unrecognized type" (e.g. a newer relay protocol addition) apart from
+/// "this frame is truncated or otherwise malformed".
+const UNKNOWN_TYPE_ERROR: u32 = 1;
+
+/// Wire error code used for a `str`/`buf`-shaped field that is required to
+/// carry a name (hdata/hashtable/infolist names, hdata keys) but was sent as
+/// the null string (`-1` length) instead.
+const MISSING_NAME_ERROR: u32 = 2;
+
+/// Like `parse_nullable_string32`, but for fields the protocol never sends
+/// null in practice (hdata/infolist names, hdata keys): a null here is
+/// malformed input rather than a value to propagate as `None`.
+fn parse_required_string32(input: &[u8]) -> IResult<&[u8], String> {
+    let (input, value) = try_parse!(input, parse_nullable_string32);
+    match value {
+        Some(value) => IResult::Done(input, value),
+        None => IResult::Error(ErrorKind::Custom(MISSING_NAME_ERROR)),
+    }
+}
+
+/// Wire error code for an element/row count that came back negative.
+const NEGATIVE_COUNT_ERROR: u32 = 3;
+
+/// Wire error code for an hdata keys string whose entries aren't all
+/// `name:type` pairs.
+const MALFORMED_KEYS_ERROR: u32 = 4;
+
+/// Reads the 4-byte count that precedes `arr`/`htb`/`inl`/`hda` elements.
+/// The wire format never sends a negative count in practice, but nothing
+/// stops a malicious or buggy peer from doing so, and casting a negative
+/// `i32` straight to `usize` turns it into a number close to `usize::MAX` -
+/// exactly what callers use to presize a `Vec`. Reject it here instead of
+/// letting `Vec::with_capacity` panic with "capacity overflow" downstream.
+fn parse_count(input: &[u8]) -> IResult<&[u8], usize> {
+    let (input, count) = try_parse!(input, be_i32);
+    if count < 0 {
+        return IResult::Error(ErrorKind::Custom(NEGATIVE_COUNT_ERROR));
+    }
+    IResult::Done(input, count as usize)
+}
+
+named!(parse_type<&[u8], String>,
+    map!(take!(3), |t: &[u8]| String::from_utf8_lossy(t).into_owned())
+);
+
+/// A string prefixed with a 4-byte length, as used for `str` and `buf`.
+/// A length of `-1` means a null string, `0` means an empty one.
+fn parse_nullable_string32(input: &[u8]) -> IResult<&[u8], Option<String>> {
+    let (input, size) = try_parse!(input, be_i32);
+    if size == -1 {
+        return IResult::Done(input, None);
+    }
+    if size == 0 {
+        return IResult::Done(input, Some(String::new()));
+    }
+    let (input, bytes) = try_parse!(input, take!(size as usize));
+    IResult::Done(input, Some(String::from_utf8_lossy(bytes).into_owned()))
 }
 
-fn read_u32(buffer: &[u8]) -> Result<u32, WeechatParseError> {
-    let mut datum = Cursor::new(buffer);
-    Ok(try!(datum.read_u32::<BigEndian>()))
+// A string prefixed with a 1-byte length, as used for `lon`, `ptr` and `tim`.
+named!(parse_string8<&[u8], String>,
+    do_parse!(
+        length: be_u8 >>
+        value: take!(length as usize) >>
+        (String::from_utf8_lossy(value).into_owned())
+    )
+);
+
+named!(parse_pointer<&[u8], String>,
+    map!(parse_string8, |mut value: String| {
+        // Pointers should have 0x at the start.
+        value.insert(0, '0');
+        value.insert(1, 'x');
+        value
+    })
+);
+
+named!(parse_char<&[u8], WeechatData>,
+    map_opt!(be_u8, |v: u8| char::from_u32(v as u32).map(WeechatData::Char))
+);
+
+fn parse_long(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, raw) = try_parse!(input, parse_string8);
+    match i64::from_str_radix(&raw, 10) {
+        Ok(value) => IResult::Done(input, WeechatData::Long(value)),
+        Err(_) => IResult::Error(ErrorKind::Digit),
+    }
 }
 
-fn read_u8(buffer: &[u8]) -> Result<u8, WeechatParseError> {
-    let mut datum = Cursor::new(buffer);
-    Ok(try!(datum.read_u8()))
+fn parse_element<'a>(input: &'a [u8], element_type: &str) -> IResult<&'a [u8], WeechatData> {
+    match element_type {
+        "chr" => parse_char(input),
+        "int" => map!(input, be_i32, WeechatData::Int),
+        "lon" => parse_long(input),
+        "str" => {
+            map!(input, parse_nullable_string32, |value: Option<String>| {
+                match value {
+                    Some(string) => WeechatData::String(string),
+                    None => WeechatData::StringNull,
+                }
+            })
+        }
+        "buf" => {
+            map!(input, parse_nullable_string32, |value: Option<String>| {
+                match value {
+                    Some(string) => WeechatData::Buffer(string),
+                    None => WeechatData::BufferNull,
+                }
+            })
+        }
+        "ptr" => map!(input, parse_pointer, WeechatData::Pointer),
+        "tim" => map!(input, parse_string8, WeechatData::Time),
+        "htb" => parse_hashtable(input),
+        "hda" => parse_hdata(input),
+        "inf" => parse_info(input),
+        "inl" => parse_infolist(input),
+        "arr" => parse_array(input),
+        _ => IResult::Error(ErrorKind::Custom(UNKNOWN_TYPE_ERROR)),
+    }
 }
 
-fn read_i32(buffer: &[u8]) -> Result<i32, WeechatParseError> {
-    let mut datum = Cursor::new(buffer);
-    Ok(try!(datum.read_i32::<BigEndian>()))
+fn parse_array(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, array_type) = try_parse!(input, parse_type);
+    let (input, count) = try_parse!(input, parse_count);
+    let mut input = input;
+    let mut acc = Vec::new();
+    for _ in 0..count {
+        let (rest, value) = try_parse!(input, apply!(parse_element, &array_type));
+        input = rest;
+        acc.push(value);
+    }
+    IResult::Done(input, WeechatData::Array(acc))
 }
 
-fn read_long(buffer: &[u8]) -> Result<(usize, i64), WeechatParseError> {
-    let (end, value) = try!(read_string_8bit_length(&buffer));
-    let long = try!(i64::from_str_radix(&value, 10));
-    Ok((end, long))
+fn parse_hashtable(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, key_type) = try_parse!(input, parse_type);
+    let (input, value_type) = try_parse!(input, parse_type);
+    let (input, count) = try_parse!(input, parse_count);
+    let mut input = input;
+    let mut acc = Vec::new();
+    for _ in 0..count {
+        let (rest, key) = try_parse!(input, apply!(parse_element, &key_type));
+        let (rest, value) = try_parse!(rest, apply!(parse_element, &value_type));
+        input = rest;
+        acc.push((key, value));
+    }
+    IResult::Done(input, WeechatData::HashTable(key_type, value_type, acc))
 }
 
-fn read_pointer(buffer: &[u8]) -> Result<(usize, String), WeechatParseError> {
-    let (end, mut value) = try!(read_string_8bit_length(&buffer));
-    // Pointers should have 0x at the start.
-    value.insert(0, '0');
-    value.insert(1, 'x');
-    Ok((end, value))
+fn parse_info(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, name) = try_parse!(input, parse_required_string32);
+    let (input, value) = try_parse!(input, parse_nullable_string32);
+    IResult::Done(input, WeechatData::Info(name, value))
 }
 
-fn read_time(buffer: &[u8]) -> Result<(usize, String), WeechatParseError> {
-    read_string_8bit_length(&buffer)
+fn parse_infolist(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, name) = try_parse!(input, parse_required_string32);
+    let (input, item_count) = try_parse!(input, parse_count);
+    let mut input = input;
+    let mut items = Vec::new();
+    for _ in 0..item_count {
+        let (rest, variable_count) = try_parse!(input, be_i32);
+        let mut rest = rest;
+        let mut variables = HashMap::new();
+        for _ in 0..variable_count {
+            let (r, var_name) = try_parse!(rest, parse_required_string32);
+            let (r, var_type) = try_parse!(r, parse_type);
+            let (r, value) = try_parse!(r, apply!(parse_element, &var_type));
+            rest = r;
+            variables.insert(var_name, value);
+        }
+        input = rest;
+        items.push(variables);
+    }
+    IResult::Done(input, WeechatData::InfoList(name, items))
 }
 
-fn read_hdata(buffer: &[u8])
-              -> Result<(usize,
-                         String,
-                         Vec<WeechatData>,
-                         Vec<HashMap<String, WeechatData>>),
-                        WeechatParseError> {
-    let mut position = 0;
-    let (name_len, name_raw) = try!(read_string_32bit_length(&buffer));
-    let name = name_raw.unwrap();
-    position += name_len;
+fn parse_hdata(input: &[u8]) -> IResult<&[u8], WeechatData> {
+    let (input, name) = try_parse!(input, parse_required_string32);
     let pointer_count = name.match_indices('/').count() + 1;
-    let (keys_len, keys_raw) = try!(read_string_32bit_length(&buffer[position..]));
-    position += keys_len;
-    let keys_owned = keys_raw.unwrap();
-    let row_count = try!(read_i32(&buffer[position..])) as usize;
-    position += 4;
+    let (input, keys_owned) = try_parse!(input, parse_required_string32);
+    let (input, row_count) = try_parse!(input, parse_count);
+    let mut input = input;
 
     let mut keys = vec![];
-    for chunk in keys_owned.split(',') {
-        let key: Vec<&str> = chunk.split(':').collect();
-        keys.push((key[0].to_owned(), key[1].to_owned()));
+    if !keys_owned.is_empty() {
+        for chunk in keys_owned.split(',') {
+            let key: Vec<&str> = chunk.split(':').collect();
+            if key.len() != 2 {
+                return IResult::Error(ErrorKind::Custom(MALFORMED_KEYS_ERROR));
+            }
+            keys.push((key[0].to_owned(), key[1].to_owned()));
+        }
     }
-    let mut pointers = Vec::with_capacity(pointer_count * row_count);
-    let mut acc = Vec::with_capacity(row_count);
+
+    let mut pointers = Vec::new();
+    let mut acc = Vec::new();
     for _ in 0..row_count {
         for _ in 0..pointer_count {
-            let (ptr_len, ptr_value) = try!(read_pointer(&buffer[position..]));
-            position += ptr_len;
+            let (rest, ptr_value) = try_parse!(input, parse_pointer);
+            input = rest;
             pointers.push(WeechatData::Pointer(ptr_value));
         }
         let mut row_data = HashMap::new();
         for &(ref key_name, ref value_type) in &keys {
-            let (len, value) = try!(parse_element(value_type, &buffer[position..]));
-            position += len;
+            let (rest, value) = try_parse!(input, apply!(parse_element, value_type));
+            input = rest;
             row_data.insert(key_name.clone(), value);
         }
         acc.push(row_data);
     }
 
-    Ok((position, name, pointers, acc))
+    IResult::Done(input, WeechatData::Hdata(name, pointers, acc))
 }
 
-fn read_string_8bit_length(buffer: &[u8]) -> Result<(usize, String), WeechatParseError> {
-    let length = try!(read_u8(&buffer)) as usize;
-    let end = length + 1;
-    let value = String::from_utf8_lossy(&buffer[1..end]).into_owned();
-    Ok((end, value))
+named!(parse_tagged_element<&[u8], WeechatData>,
+    do_parse!(
+        element_type: parse_type >>
+        value: apply!(parse_element, &element_type) >>
+        (value)
+    )
+);
+
+fn parse_data(input: &[u8]) -> IResult<&[u8], Vec<WeechatData>> {
+    let mut input = input;
+    let mut acc = vec![];
+    while !input.is_empty() {
+        match parse_tagged_element(input) {
+            IResult::Done(rest, value) => {
+                input = rest;
+                acc.push(value);
+            }
+            IResult::Error(e) => return IResult::Error(e),
+            IResult::Incomplete(n) => return IResult::Incomplete(n),
+        }
+    }
+    IResult::Done(input, acc)
 }
 
-fn read_string_32bit_length(buffer: &[u8]) -> Result<(usize, Option<String>), WeechatParseError> {
-    let size = try!(read_i32(buffer));
+pub fn get_length(buffer: &[u8]) -> Result<u32, WeechatParseError> {
+    nom_to_result(be_u32(buffer), "message length")
+}
 
-    if size == 0 {
-        return Ok((4, Some("".to_owned())))
-    }
-    if size == -1 {
-        return Ok((4, None))
-    }
-    let end = (size + 4) as usize;
-    let raw_string = &buffer[4..end];
-    let value = String::from_utf8_lossy(raw_string);
-    Ok((end, Some(value.into_owned())))
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Compression {
+    None,
+    Zlib,
+    Zstd,
+}
 
+pub fn get_compression(buffer: &[u8]) -> Result<Compression, WeechatParseError> {
+    match buffer.get(4) {
+        Some(&0) => Ok(Compression::None),
+        Some(&1) => Ok(Compression::Zlib),
+        Some(&2) => Ok(Compression::Zstd),
+        Some(flag) => {
+            fail!((MalformedBinaryParse,
+                   "Unknown compression flag",
+                   flag.to_string()))
+        }
+        None => fail!((MalformedBinaryParse, "Could not find compression flag")),
+    }
 }
 
-fn read_array(buffer: &[u8]) -> Result<(usize, Vec<WeechatData>), WeechatParseError> {
-    let array_type = get_element_type(&buffer);
-    let mut position = 3;
-    let count = try!(read_i32(&buffer[position..]));
-    position += 4;
-    let mut acc = Vec::<WeechatData>::with_capacity(count as usize);
-    match array_type.as_ref() {
-        "str" => {
-            for _ in 0..count {
-                let (len, value) = try!(read_string_32bit_length(&buffer[position..]));
-                match value {
-                    Some(string) => acc.push(WeechatData::String(string)),
-                    None => acc.push(WeechatData::StringNull),
-                }
-                position += len;
-            }
+fn get_message_type(buffer: &[u8]) -> Result<(usize, Option<String>), WeechatParseError> {
+    match parse_nullable_string32(buffer) {
+        IResult::Done(rest, value) => Ok((buffer.len() - rest.len(), value)),
+        IResult::Error(e) => {
+            fail!((MalformedBinaryParse,
+                   "Malformed message type",
+                   format!("{:?}", e)))
         }
-        "int" => {
-            for _ in 0..count {
-                let value = try!(read_i32(&buffer[position..]));
-                acc.push(WeechatData::Int(value));
-                position += 4;
-            }
+        IResult::Incomplete(needed) => {
+            fail!((MalformedBinaryParse,
+                   "Message ended before the message type was fully read",
+                   format!("{:?}", needed)))
         }
-        _ => fail!((UnknownType,
-                    "array isn't implemented for type",
-                    format!("found array type {:?}", array_type))),
-    };
-    Ok((position, acc))
+    }
 }
 
-pub fn get_length(buffer: &[u8]) -> Result<u32, WeechatParseError> {
-    read_u32(buffer)
+fn get_raw_data(buffer: &[u8]) -> Result<Vec<u8>, WeechatParseError> {
+    let payload = match buffer.get(5..) {
+        Some(bytes) => bytes,
+        None => fail!((MalformedBinaryParse, "Message shorter than the 5-byte header")),
+    };
+    match try!(get_compression(buffer)) {
+        Compression::None => Ok(payload.to_vec()),
+        Compression::Zlib => {
+            let mut decoder = ZlibDecoder::new(payload);
+            let mut result = Vec::<u8>::new();
+            try!(decoder.read_to_end(&mut result));
+            Ok(result)
+        }
+        Compression::Zstd => {
+            let mut decoder = try!(ZstdDecoder::new(payload));
+            let mut result = Vec::<u8>::new();
+            try!(decoder.read_to_end(&mut result));
+            Ok(result)
+        }
+    }
 }
 
-pub fn get_compression(buffer: &[u8]) -> Result<bool, WeechatParseError> {
-    if let Some(flag) = buffer.get(4) {
-        Ok(flag == &1)
-    } else {
-        fail!((MalformedBinaryParse, "Could not find compression flag"))
-    }
+#[test]
+fn test_command_init() {
+    let command = WeechatCommand::init("secret", Compression::Zstd).out();
+    assert_eq!(String::from_utf8(command).unwrap(),
+               "init password=secret,compression=zstd\n");
 }
 
-fn get_message_type(buffer: &[u8]) -> Result<(usize, Option<String>), WeechatParseError> {
-    read_string_32bit_length(&buffer)
+#[test]
+fn test_command_hdata_and_sync_with_id() {
+    let hdata = WeechatCommand::hdata("buffer:gui_buffers(*)", &["number", "name"])
+        .with_id("buffers")
+        .out();
+    assert_eq!(String::from_utf8(hdata).unwrap(),
+               "(buffers)hdata buffer:gui_buffers(*) number,name\n");
+
+    let sync = WeechatCommand::sync(&[]).out();
+    assert_eq!(String::from_utf8(sync).unwrap(), "sync\n");
 }
 
-fn get_raw_data(buffer: &[u8]) -> Result<Vec<u8>, WeechatParseError> {
-    let mut datum = Cursor::new(buffer);
-    datum.set_position(5);
-    let mut decoder = ZlibDecoder::new(datum);
-    let mut result = Vec::<u8>::new();
-    try!(decoder.read_to_end(&mut result));
-    Ok(result)
+#[test]
+fn test_command_ping() {
+    let ping = WeechatCommand::ping("1234").out();
+    assert_eq!(String::from_utf8(ping).unwrap(), "ping 1234\n");
 }
 
 #[test]
@@ -378,22 +594,219 @@ fn test_parse_test_data() {
     } else {
         panic!("got wrong type in test element 14 (expected Array)");
     }
-    // uncompressed data blob.
-    // [255, 255, 255, 255, 99, 104, 114, 65, 105, 110, 116, 0, 1, 226, 64, 105,
-    //  110, 116, 255, 254, 29, 192, 108, 111, 110, 10, 49, 50, 51, 52, 53, 54,
-    //  55, 56, 57, 48, 108, 111, 110, 11, 45, 49, 50, 51, 52, 53, 54, 55, 56, 57,
-    //  48, 115, 116, 114, 0, 0, 0, 8, 97, 32, 115, 116, 114, 105, 110, 103, 115,
-    //  116, 114, 0, 0, 0, 0, 115, 116, 114, 255, 255, 255, 255, 98, 117, 102, 0,
-    //  0, 0, 6, 98, 117, 102, 102, 101, 114, 98, 117, 102, 255, 255, 255, 255, 112,
-    //  116, 114, 8, 49, 50, 51, 52, 97, 98, 99, 100, 112, 116, 114, 1, 48, 116,
-    //  105, 109, 10, 49, 51, 50, 49, 57, 57, 51, 52, 53, 54, 97, 114, 114, 115,
-    //  116, 114, 0, 0, 0, 2, 0, 0, 0, 3, 97, 98, 99, 0, 0, 0, 2, 100, 101, 97, 114,
-    //  114, 105, 110, 116, 0, 0, 0, 3, 0, 0, 0, 123, 0, 0, 1, 200, 0, 0, 3, 21]
     assert_eq!(get_length(&data).unwrap(), 145);
-    assert_eq!(get_compression(&data).unwrap(), true);
+    assert_eq!(get_compression(&data).unwrap(), Compression::Zlib);
     let raw_data = get_raw_data(&data).unwrap();
     let (type_jump, message_type) = get_message_type(&raw_data).unwrap();
     assert_eq!(type_jump, 4);
     assert_eq!(message_type, None);
-    assert_eq!(get_element_type(&raw_data[type_jump..]), "chr".to_owned());
+    let element_type = String::from_utf8_lossy(&raw_data[type_jump..type_jump + 3]).into_owned();
+    assert_eq!(element_type, "chr".to_owned());
+}
+
+#[test]
+fn test_zstd_compressed_message() {
+    // length(4) + compression flag(1, zstd) + a real zstd frame compressing
+    // id(-1) + chr 'A' + int 42.
+    let data = [0, 0, 0, 29, 2, 40, 181, 47, 253, 0, 72, 121, 0, 0, 255, 255, 255, 255, 99, 104,
+                114, 65, 105, 110, 116, 0, 0, 0, 42];
+
+    assert_eq!(get_compression(&data).unwrap(), Compression::Zstd);
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    assert_eq!(message.id, "test".to_owned());
+    assert_eq!(message.data.get(0), Some(&WeechatData::Char('A')));
+    assert_eq!(message.data.get(1), Some(&WeechatData::Int(42)));
+}
+
+#[test]
+fn test_uncompressed_message() {
+    // length(4) + compression flag(1, none) + id(-1) + chr 'A' + int 42
+    let data = [0, 0, 0, 20, 0, 255, 255, 255, 255, 99, 104, 114, 65, 105, 110, 116, 0, 0, 0, 42];
+
+    assert_eq!(get_compression(&data).unwrap(), Compression::None);
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    assert_eq!(message.id, "test".to_owned());
+    assert_eq!(message.data.get(0), Some(&WeechatData::Char('A')));
+    assert_eq!(message.data.get(1), Some(&WeechatData::Int(42)));
+}
+
+#[test]
+fn test_hashtable() {
+    // htb{str -> int}: {"a": 1, "b": 2}
+    let data = [0, 0, 0, 40, 0, 255, 255, 255, 255, 104, 116, 98, 115, 116, 114, 105, 110, 116, 0,
+                0, 0, 2, 0, 0, 0, 1, 97, 0, 0, 0, 1, 0, 0, 0, 1, 98, 0, 0, 0, 2];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    if let WeechatData::HashTable(ref key_type, ref value_type, ref pairs) =
+           *message.data.get(0).unwrap() {
+        assert_eq!(key_type, "str");
+        assert_eq!(value_type, "int");
+        assert_eq!(pairs,
+                   &vec![(WeechatData::String("a".to_owned()), WeechatData::Int(1)),
+                         (WeechatData::String("b".to_owned()), WeechatData::Int(2))]);
+    } else {
+        panic!("got wrong type in test element 0 (expected HashTable)");
+    }
+}
+
+#[test]
+fn test_info() {
+    // inf "version" -> "1.0"
+    let data = [0, 0, 0, 30, 0, 255, 255, 255, 255, 105, 110, 102, 0, 0, 0, 7, 118, 101, 114,
+                115, 105, 111, 110, 0, 0, 0, 3, 49, 46, 48];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    assert_eq!(message.data.get(0),
+               Some(&WeechatData::Info("version".to_owned(), Some("1.0".to_owned()))));
+}
+
+#[test]
+fn test_infolist() {
+    // inl "buffers" with one item: {name: "weechat", number: 1}
+    let data = [0, 0, 0, 70, 0, 255, 255, 255, 255, 105, 110, 108, 0, 0, 0, 7, 98, 117, 102, 102,
+                101, 114, 115, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 4, 110, 97, 109, 101, 115, 116,
+                114, 0, 0, 0, 7, 119, 101, 101, 99, 104, 97, 116, 0, 0, 0, 6, 110, 117, 109, 98,
+                101, 114, 105, 110, 116, 0, 0, 0, 1];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    if let WeechatData::InfoList(ref name, ref items) = *message.data.get(0).unwrap() {
+        assert_eq!(name, "buffers");
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].get("name"), Some(&WeechatData::String("weechat".to_owned())));
+        assert_eq!(items[0].get("number"), Some(&WeechatData::Int(1)));
+    } else {
+        panic!("got wrong type in test element 0 (expected InfoList)");
+    }
+}
+
+#[test]
+fn test_hdata_with_no_keys() {
+    // hda "buffer" requested with an empty (not null) keys string and no
+    // rows, as produced by WeechatCommand::hdata(path, &[]).
+    let data = [0, 0, 0, 30, 0, 255, 255, 255, 255, 104, 100, 97, 0, 0, 0, 6, 98, 117, 102, 102,
+                101, 114, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    if let WeechatData::Hdata(ref name, ref pointers, ref rows) = *message.data.get(0).unwrap() {
+        assert_eq!(name, "buffer");
+        assert_eq!(pointers, &vec![]);
+        assert_eq!(rows, &vec![]);
+    } else {
+        panic!("got wrong type in test element 0 (expected Hdata)");
+    }
+}
+
+#[test]
+fn test_info_with_null_name_is_an_error() {
+    // inf with a null (-1-length) name instead of a panic-inducing .unwrap().
+    let data = [0, 0, 0, 20, 0, 255, 255, 255, 255, 105, 110, 102, 255, 255, 255, 255, 255, 255,
+                255, 255];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_infolist_with_null_name_is_an_error() {
+    // inl with a null name and zero items.
+    let data = [0, 0, 0, 20, 0, 255, 255, 255, 255, 105, 110, 108, 255, 255, 255, 255, 0, 0, 0,
+                0];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_hdata_with_null_name_is_an_error() {
+    // hda with a null name, null keys and zero rows.
+    let data = [0, 0, 0, 24, 0, 255, 255, 255, 255, 104, 100, 97, 255, 255, 255, 255, 255, 255,
+                255, 255, 0, 0, 0, 0];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_negative_array_count_is_an_error() {
+    // arr{int} claiming a count of -1 instead of panicking with "capacity
+    // overflow" when that count is cast to usize.
+    let data = [0, 0, 0, 19, 0, 255, 255, 255, 255, 97, 114, 114, 105, 110, 116, 255, 255, 255,
+                255];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_hdata_with_malformed_keys_is_an_error() {
+    // hda "buffer" with a non-empty but malformed keys string (a trailing
+    // comma leaves an entry with no ":type").
+    let data = [0, 0, 0, 41, 0, 255, 255, 255, 255, 104, 100, 97, 0, 0, 0, 6, 98, 117, 102, 102,
+                101, 114, 0, 0, 0, 11, 110, 117, 109, 98, 101, 114, 58, 105, 110, 116, 44, 0, 0,
+                0, 0];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_pointer_array() {
+    let data = [0, 0, 0, 30, 0, 255, 255, 255, 255, 97, 114, 114, 112, 116, 114, 0, 0, 0, 2, 8,
+                49, 50, 51, 52, 97, 98, 99, 100, 1, 48];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    if let WeechatData::Array(ref pointers) = *message.data.get(0).unwrap() {
+        assert_eq!(pointers,
+                   &vec![WeechatData::Pointer("0x1234abcd".to_owned()),
+                         WeechatData::Pointer("0x0".to_owned())]);
+    } else {
+        panic!("got wrong type in test element 0 (expected Array)");
+    }
+}
+
+#[test]
+fn test_time_array() {
+    let data = [0, 0, 0, 32, 0, 255, 255, 255, 255, 97, 114, 114, 116, 105, 109, 0, 0, 0, 2, 10,
+                49, 51, 50, 49, 57, 57, 51, 52, 53, 54, 1, 48];
+
+    let message = WeechatMessage::from_raw_message(&data).unwrap();
+    if let WeechatData::Array(ref times) = *message.data.get(0).unwrap() {
+        assert_eq!(times,
+                   &vec![WeechatData::Time("1321993456".to_owned()),
+                         WeechatData::Time("0".to_owned())]);
+    } else {
+        panic!("got wrong type in test element 0 (expected Array)");
+    }
+}
+
+#[test]
+fn test_truncated_message_is_an_error() {
+    // Claims an "int" element (4 bytes) but only provides 2.
+    let data = [0, 0, 0, 14, 0, 255, 255, 255, 255, 105, 110, 116, 0, 0];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::MalformedBinaryParse => {}
+        other => panic!("expected a MalformedBinaryParse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unknown_element_type_is_reported_distinctly() {
+    // "xyz" isn't a wire type this crate knows about.
+    let data = [0, 0, 0, 12, 0, 255, 255, 255, 255, 120, 121, 122];
+
+    match WeechatMessage::from_raw_message(&data) {
+        Err(ref err) if err.kind == errors::ErrorKind::UnknownType => {}
+        other => panic!("expected an UnknownType error, got {:?}", other),
+    }
 }